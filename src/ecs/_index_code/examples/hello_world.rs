@@ -5,12 +5,13 @@ use std::fmt;
 // Denizen is a "marker component" here, to allow us to filter for denizens in our queries
 // In general, marker components do not store data, and are just used for queries
 // Marker components are talked about in more detail in section 2.2: Components
-#[derive(Debug)]
+// `Component` is opt-in, so every type we spawn into the world needs the derive
+#[derive(Component, Debug)]
 struct Denizen;
 
-// We use the owned form String, rather than &str in this struct 
+// We use the owned form String, rather than &str in this struct
 // Because resources and components must be thread-safe with a 'static lifetime
-#[derive(Hash, PartialEq, Eq, Clone, Debug)]
+#[derive(Component, Hash, PartialEq, Eq, Clone, Debug)]
 struct Name(String);
 
 // A custom impl of Display to ensure we can print these names nicely
@@ -20,7 +21,7 @@ impl fmt::Display for Name{
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Component, Clone, Debug)]
 enum World{
 	Venus,
 	Earth,
@@ -28,39 +29,106 @@ enum World{
 }
 
 // Creating a unique timer type for each system that needs one allows us to be sure we're grabbing the right one
+#[derive(Resource)]
 struct HelloTimer(Timer);
 
+// A dedicated timer for `migrate_denizens`, following the same one-timer-per-system convention as `HelloTimer`
+#[derive(Resource)]
+struct MigrationTimer(Timer);
+
+// Fired whenever a new denizen should join the world at runtime, rather than only
+// during the startup systems. Anything with access to `EventWriter<DenizenArrived>`
+// can register a new inhabitant on any frame
+#[derive(Event)]
+struct DenizenArrived{
+	name: Name,
+	world: World,
+	is_resident: bool,
+}
+
+// Wrapping the name -> world map in a newtype, rather than inserting the bare `HashMap` as
+// a resource, keeps its internals private behind accessor methods and lets us opt it into
+// `Resource` explicitly instead of relying on a blanket impl
+#[derive(Clone, Default, Resource)]
+struct Directory(HashMap<Name, World>);
+
+impl Directory{
+	fn insert(&mut self, name: Name, world: World){
+		self.0.insert(name, world);
+	}
+}
+
+// Delegating IntoIterator to the inner HashMap lets callers keep writing
+// `directory.clone().into_iter()` without reaching into the newtype's internals
+impl IntoIterator for Directory{
+	type Item = (Name, World);
+	type IntoIter = std::collections::hash_map::IntoIter<Name, World>;
+
+	fn into_iter(self) -> Self::IntoIter{
+		self.0.into_iter()
+	}
+}
+
+// The save/restore resource populated by `snapshot_world` and consumed by `restore_world`.
+// Each tuple is everything `restore_world` needs to respawn one denizen: its name, which
+// world it was on, and whether it carried the `Denizen` marker
+#[derive(Clone, Default, Resource)]
+struct WorldSnapshot{
+	denizens: Vec<(Name, World, bool)>,
+}
+
+// Bundling all of the denizen logic into its own `Plugin` lets other apps pull it in
+// wholesale with `.add_plugins(DenizenPlugin { greet_interval: 5.0 })`, the same pattern
+// the official hello-world examples use for `HelloPlugin`/`GamarjobaPlugin`
+pub struct DenizenPlugin{
+	// Exposing this as a public field lets callers tune how often `say_hello` fires
+	// without having to fork the plugin
+	pub greet_interval: f32,
+}
+
+impl Plugin for DenizenPlugin{
+	fn build(&self, app: &mut App){
+		app
+		// We're creating a Directory to record which planet entities a name are on
+		.insert_resource(Directory::default())
+		// Startup systems only run once, before normal systems take place; `.chain()`
+		// keeps `place_denizens` running before `spawn_denizens` reads the directory it fills
+		.add_systems(Startup, (place_denizens, spawn_denizens).chain())
+		// `DenizenArrived` lets anything send a new denizen to `spawn_arrivals` on a later frame
+		.add_event::<DenizenArrived>()
+		// The `say_hello` system runs every frame, but only prints when its timer is complete
+		// (See `say_hello` logic)
+		.insert_resource(HelloTimer(Timer::from_seconds(self.greet_interval, TimerMode::Repeating)))
+		// Denizens drift to a new planet on their own timer, set to twice `greet_interval`, so they move half as often as they are greeted
+		.insert_resource(MigrationTimer(Timer::from_seconds(self.greet_interval * 2.0, TimerMode::Repeating)))
+		// F5 snapshots the current population, F9 restores the last snapshot taken
+		.insert_resource(WorldSnapshot::default())
+		.add_systems(Update, (spawn_arrivals, say_hello, migrate_denizens, snapshot_world, restore_world));
+	}
+}
+
 fn main() {
-	App::build()
+	App::new()
 	// We add the default plugins so our game will loop
 	// This also adds the Time resource that we use in `say_hello`
 	.add_plugins(DefaultPlugins)
-	// We're creating a HashMap to record which planet entities a name are on
-	.add_resource(HashMap::<Name, World>::new())
-	// Startup systems only run once, before normal systems take place
-	.add_startup_system(place_denizens.system())
-	// When systems cannot be run in parallel, priority is based on insertion order
-	.add_startup_system(spawn_denizens.system())
-	// The `say_hello` system will be run every frame, but only prints when the timer is complete
-	// (See `say_hello` logic)
-	.add_resource(HelloTimer(Timer::from_seconds(2.0, true)))
-	.add_system(say_hello.system())
+	.add_plugins(DenizenPlugin{ greet_interval: 2.0 })
 	.run();
 }
 
 // Because we're modifying the `directory` argument, we need to get the mutable version of it with `ResMut`
 // Bevy's ECS finds a Resource with the matching type; we want to be sure we have exactly one resource of each type that we need
-fn place_denizens(mut directory: ResMut<HashMap::<Name, World>>){
+fn place_denizens(mut directory: ResMut<Directory>){
 	// .into() converts our string literal from &str to the required String
 	directory.insert(Name("Alice".into()), World::Venus);
 	directory.insert(Name("Bevy".into()), World::Earth);
 	directory.insert(Name("Cart".into()), World::Mars);
 }
 
-// The special `Commands` resource queues up actions that should be performed to modify the World
+// `Commands` queues up actions that should be performed to modify the World
 // We only need to read from the directory resource, so we can call it with `Res` instead of `ResMut`
-fn spawn_denizens(commands: &mut Commands, directory: Res<HashMap::<Name, World>>){
-	// We need to use .clone and .into_iter rather than .iter here 
+fn spawn_denizens(mut commands: Commands, directory: Res<Directory>){
+	// We need to use .clone and .into_iter rather than .iter here
 	// to satisfy the lifetime requirements of .spawn()
 	for (name, world) in directory.clone().into_iter(){
 		if name == Name("Bevy".into()){
@@ -74,12 +142,46 @@ fn spawn_denizens(commands: &mut Commands, directory: Res<HashMap::<Name, World>
 	}
 }
 
+// `EventReader` drains whatever `DenizenArrived` events were sent since this system last ran,
+// and `Commands` lets us queue up the matching spawn alongside them, the same deferred
+// command pattern `spawn_denizens` above already relies on
+fn spawn_arrivals(mut commands: Commands, mut arrivals: EventReader<DenizenArrived>, mut directory: ResMut<Directory>){
+	for arrival in arrivals.read(){
+		directory.insert(arrival.name.clone(), arrival.world.clone());
+		if arrival.is_resident{
+			// Only residents get the `Denizen` marker, matching the Bevy-vs-resident
+			// distinction `spawn_denizens` draws for Bevy above
+			commands.spawn((arrival.name.clone(), arrival.world.clone(), Denizen));
+		} else {
+			commands.spawn((arrival.name.clone(), arrival.world.clone()));
+		}
+	}
+}
+
+// This system mutates a component in place instead of just reading it, so the query asks for
+// `&mut World` and we iterate with `&mut query` (`for ... in &mut query`) rather than `.iter_mut()`,
+// per Bevy's push towards `IntoIterator`-based loops
+fn migrate_denizens(mut query: Query<(&Name, &mut World), With<Denizen>>, mut directory: ResMut<Directory>, mut timer: ResMut<MigrationTimer>, time: Res<Time>){
+	if !timer.0.tick(time.delta()).just_finished(){
+		return;
+	}
+	for (name, mut world) in &mut query{
+		*world = match *world{
+			World::Venus => World::Earth,
+			World::Earth => World::Mars,
+			World::Mars => World::Venus,
+		};
+		// Keep the directory resource pointing at each denizen's new home
+		directory.insert(name.clone(), world.clone());
+	}
+}
+
 // Queries extract each entity that have all of the components specified in their first type argument
 // They only return the components specified in the query, not any other components that may be associated with the entities
-// The second type argument is a query filter, which restricts which entities are actually provided 
+// The second type argument is a query filter, which restricts which entities are actually provided
 fn say_hello(query: Query<(&Name, &World), With<Denizen>>, mut timer: ResMut<HelloTimer>, time: Res<Time>){
 	// Only run this system when the timer has elapsed
-	if timer.0.tick(time.delta_seconds()).just_finished(){
+	if timer.0.tick(time.delta()).just_finished(){
 		// Iterating over and then unpacking the query gives us access to the components for each of its entities
 		for (name, world) in query.iter(){
 			// Because we're querying for &Query and &World, we need to dereference them before we work with them
@@ -87,3 +189,71 @@ fn say_hello(query: Query<(&Name, &World), With<Denizen>>, mut timer: ResMut<Hel
 		}
 	}
 }
+
+// A system whose only parameter is `&mut bevy::prelude::World` runs exclusively and
+// single-threaded, with immediate access to every entity and resource. We spell the type
+// out in full here because our own `World` enum already shadows the glob import from the
+// prelude. That direct access is exactly what the restore half of this pair needs: reading
+// the full entity set, despawning it, and spawning the snapshot back in, all without
+// fighting the scheduler's usual aliasing rules
+fn snapshot_world(world: &mut bevy::prelude::World){
+	let pressed_f5 = world
+		.get_resource::<ButtonInput<KeyCode>>()
+		.map_or(false, |keys| keys.just_pressed(KeyCode::F5));
+	if !pressed_f5{
+		return;
+	}
+
+	let entities: Vec<(Entity, Name, World)> = world
+		.query::<(Entity, &Name, &World)>()
+		.iter(world)
+		.map(|(entity, name, planet)| (entity, name.clone(), planet.clone()))
+		.collect();
+
+	let denizens = entities
+		.into_iter()
+		.map(|(entity, name, planet)| {
+			let is_resident = world.get::<Denizen>(entity).is_some();
+			(name, planet, is_resident)
+		})
+		.collect();
+
+	world.insert_resource(WorldSnapshot{ denizens });
+}
+
+fn restore_world(world: &mut bevy::prelude::World){
+	let pressed_f9 = world
+		.get_resource::<ButtonInput<KeyCode>>()
+		.map_or(false, |keys| keys.just_pressed(KeyCode::F9));
+	if !pressed_f9{
+		return;
+	}
+
+	let denizens = world.get_resource::<WorldSnapshot>().unwrap().denizens.clone();
+	if denizens.is_empty(){
+		// No snapshot has been taken yet (F5); restoring an empty one would just
+		// wipe out the current population, so treat this as a no-op instead
+		return;
+	}
+
+	// Despawning and respawning in the same exclusive pass is why this needs `&mut World`:
+	// no `SystemParam`-based system can hold a `Query` and `Commands` for the same
+	// entities without the scheduler complaining about aliasing
+	let current: Vec<Entity> = world.query::<(Entity, &Name)>().iter(world).map(|(entity, _)| entity).collect();
+	for entity in current{
+		world.despawn(entity);
+	}
+
+	let mut directory = Directory::default();
+	for (name, planet, is_resident) in denizens{
+		directory.insert(name.clone(), planet.clone());
+		if is_resident{
+			// `bevy::prelude::World::spawn` takes the bundle directly, rather than an empty entity
+			// followed by a separate `insert_bundle` call
+			world.spawn((name, planet, Denizen));
+		} else {
+			world.spawn((name, planet));
+		}
+	}
+	world.insert_resource(directory);
+}